@@ -1,18 +1,22 @@
 use chrono::Days;
 use clap::Parser;
-use core::error::Error;
 use email::MessageTemplate;
 use lettre::{SmtpTransport, Transport};
 use plot::{pie, Quantity};
-use rspamd::{load_rspamd_statistics, MessageActions};
-use spam::{domain_report, load_spam_maildir, load_spam_virtual_mailbox_base};
+use rspamd::{load_rspamd_statistics, MessageActions, RspamdError};
+use spam::{
+    domain_report, load_spam_maildir, load_spam_virtual_mailbox_base, load_spamtrap,
+    partition_spamtraps, source_report, spamtrap_mailbox, spamtrap_report, LoadedSpam,
+};
 use statistics::{
-    last_n_days, misclassification_rate, quantize_spam_results, IntoBins, WeeklyBins,
+    last_n_days, misclassification_rate, quantize_spam_results, IntoBins, Occurrences, Source,
+    SpamEmail, SpamResults, WeeklyBins,
 };
 use std::{
     ffi::{c_char, CStr},
     io,
     path::Path,
+    process::ExitCode,
 };
 
 mod email;
@@ -25,6 +29,51 @@ mod statistics;
 const WEEKLY_CHART_WINDOW: u64 = 30;
 // Max number of days to include in daily charts
 const DAILY_CHART_WINDOW: u64 = 14;
+// Max number of senders to include in the spamtrap report
+const SPAMTRAP_REPORT_SENDERS: usize = 10;
+
+/// The reasons this program can fail. Each is reported with a distinct exit code, so that
+/// schedulers like cron can alert on them.
+#[derive(Debug, thiserror::Error)]
+enum Failure {
+    /// A source couldn't be loaded, or, if `--fail-on-parse-error` was given, some messages failed
+    /// to parse
+    #[error("failed to load spam: {0:#}")]
+    Parse(anyhow::Error),
+    /// `rspamc stat` couldn't be run
+    #[error("rspamd is unavailable: {0}")]
+    Rspamd(#[from] RspamdError),
+    /// No spam was found, and `--fail-on-empty` was given
+    #[error("no spam was found")]
+    EmptyData,
+    /// The report couldn't be delivered, and `--fail-on-send-error` was given
+    #[error("failed to send email: {0}")]
+    Delivery(lettre::transport::smtp::Error),
+    /// Any other error, such as failing to build the report. Exits with code 1
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl Failure {
+    fn exit_code(&self) -> ExitCode {
+        // Exit code 2 is reserved by clap for usage errors
+        match self {
+            Failure::Other(_) => ExitCode::from(1),
+            Failure::Parse(_) => ExitCode::from(3),
+            Failure::Rspamd(_) => ExitCode::from(4),
+            Failure::EmptyData => ExitCode::from(5),
+            Failure::Delivery(_) => ExitCode::from(6),
+        }
+    }
+}
+
+/// Controls which conditions are treated as failures.
+#[derive(Clone, Copy)]
+struct FailurePolicy {
+    fail_on_parse_error: bool,
+    fail_on_empty: bool,
+    fail_on_send_error: bool,
+}
 
 fn get_hostname() -> Result<String, anyhow::Error> {
     let mut buffer: [u8; 64] = [0; 64];
@@ -77,14 +126,36 @@ fn action_breakdown(
     ]
 }
 
-fn spam_statistics<P, Q>(
+fn source_breakdown(spam: &[SpamEmail]) -> Vec<pie::Slice> {
+    let total = spam.len() as f64;
+    spam.iter()
+        .map(|email| &email.source)
+        .into_bins()
+        .zip(pie::Color::ALL.into_iter().cycle())
+        .map(|((source, occurrences), color)| pie::Slice {
+            label: format!(
+                "{} ({}, {:.1}%)",
+                source,
+                occurrences,
+                ((occurrences as f64) / total) * 100.0
+            ),
+            color,
+            ratio: (occurrences as f64) / total,
+        })
+        .collect()
+}
+
+fn spam_statistics<P, Q, S>(
     domain: &str,
     virtual_mailbox_base: P,
     maildirs: &[Q],
-) -> Result<(), Box<dyn Error>>
+    spamtraps: &[S],
+    policy: FailurePolicy,
+) -> Result<(), Failure>
 where
     P: AsRef<Path>,
     Q: AsRef<Path>,
+    S: AsRef<str>,
 {
     let rspamc_stat = load_rspamd_statistics()?;
     let message_actions = action_breakdown(&rspamc_stat.message_actions);
@@ -98,17 +169,55 @@ where
     }
     .make_pie();
 
+    // Spamtrap mailboxes are loaded in full separately, so they're left out of the scan of the
+    // virtual mailbox base
+    let virtual_mailbox_base = virtual_mailbox_base.as_ref();
+    let spamtrap_mailboxes = spamtraps
+        .iter()
+        .filter_map(|trap| spamtrap_mailbox(virtual_mailbox_base, trap.as_ref()))
+        .collect::<Vec<_>>();
+
     // TODO: Encode the sorted invariant here somewhere, because everything after this depends on
     // it being sorted
-    let mut spam_results = load_spam_virtual_mailbox_base(virtual_mailbox_base)?;
-    for maildir in maildirs {
-        if let Ok(results) = load_spam_maildir(maildir) {
-            spam_results.extend(results);
+    let mut sources =
+        vec![
+            load_spam_virtual_mailbox_base(virtual_mailbox_base, &spamtrap_mailboxes)
+                .map_err(Failure::Parse)?,
+        ];
+
+    // Sources that fail to load are still recorded, so the report shows which input is missing
+    let failed = |source: Source, error: anyhow::Error| {
+        eprintln!("failed to load {}: {:#}", source, error);
+        LoadedSpam::failed(source, error)
+    };
+    for trap in spamtraps {
+        let trap = trap.as_ref();
+        match load_spamtrap(virtual_mailbox_base, trap) {
+            Ok(Some(loaded)) => sources.push(loaded),
+            Ok(None) => {}
+            Err(error) => {
+                let mailbox =
+                    spamtrap_mailbox(virtual_mailbox_base, trap).unwrap_or_else(|| trap.into());
+                sources.push(failed(Source::Spamtrap(mailbox), error));
+            }
         }
     }
+    for maildir in maildirs {
+        sources.push(load_spam_maildir(maildir).unwrap_or_else(|error| {
+            failed(Source::Maildir(maildir.as_ref().to_path_buf()), error)
+        }));
+    }
+
+    let mut spam_results = SpamResults::new();
+    for loaded in sources.iter_mut() {
+        spam_results.append(&mut loaded.spam);
+    }
 
     spam_results.sort_by(|one, two| one.date_received.cmp(&two.date_received));
 
+    // Spamtrap hits are known to be spam, so they're kept out of the misclassification metrics
+    let (spamtrap_results, classified_results) = partition_spamtraps(&spam_results, spamtraps);
+
     let images = if !spam_results.is_empty() {
         vec![
             // Frequency of X-Spam-Result values
@@ -125,7 +234,7 @@ where
                 domain: "Week of".into(),
                 range: "Percent".into(),
                 data: misclassification_rate(
-                    spam_results
+                    classified_results
                         .iter()
                         .weekly_bins()
                         .take_weeks(WEEKLY_CHART_WINDOW),
@@ -157,19 +266,38 @@ where
                     .into_bins(),
             }
             .make_histogram(),
+            // Volume of spam loaded from each source
+            Quantity {
+                name: format!("Spam Volume by Source for {}", domain),
+                domain: "Source".into(),
+                range: "Percentage".into(),
+                data: source_breakdown(&spam_results).as_slice(),
+            }
+            .make_pie(),
         ]
     } else {
         println!("No spam.");
         vec![]
     };
 
-    let template = MessageTemplate::new(domain.into(), "postmaster".into())?;
-    let text_content =
-        rspamd::stat_report(rspamc_stat) + "\n" + &domain_report(spam_results.into_iter());
-    let email = template.make_message(
-        [rspamd_image].into_iter().chain(images.into_iter()),
-        text_content,
-    )?;
+    let is_empty = spam_results.is_empty();
+    let template =
+        MessageTemplate::new(domain.into(), "postmaster".into()).map_err(anyhow::Error::from)?;
+    let mut text_content = rspamd::stat_report(rspamc_stat)
+        + "\n"
+        + &source_report(&spam_results, &sources)
+        + "\n"
+        + &domain_report(classified_results.into_iter());
+    if !spamtraps.is_empty() {
+        text_content += "\n";
+        text_content += &spamtrap_report(&spamtrap_results, SPAMTRAP_REPORT_SENDERS);
+    }
+    let email = template
+        .make_message(
+            [rspamd_image].into_iter().chain(images.into_iter()),
+            text_content,
+        )
+        .map_err(anyhow::Error::from)?;
 
     // Create SMTP client for localhost:25
     let mailer = SmtpTransport::unencrypted_localhost();
@@ -177,25 +305,106 @@ where
     // Send the email
     match mailer.send(&email) {
         Ok(_) => println!("Email sent successfully."),
+        Err(e) if policy.fail_on_send_error => return Err(Failure::Delivery(e)),
         Err(e) => eprintln!("Failed to send email: {e}"),
     }
 
+    let load_errors = sources
+        .iter()
+        .filter(|loaded| loaded.load_error.is_some())
+        .count();
+    if load_errors > 0 {
+        let error = anyhow::anyhow!("{} sources failed to load", load_errors);
+        return Err(Failure::Parse(error));
+    }
+
+    let parse_errors: Occurrences = sources.iter().map(|loaded| loaded.parse_errors).sum();
+    if parse_errors > 0 && policy.fail_on_parse_error {
+        let error = anyhow::anyhow!("{} messages failed to parse", parse_errors);
+        return Err(Failure::Parse(error));
+    }
+
+    if is_empty && policy.fail_on_empty {
+        return Err(Failure::EmptyData);
+    }
+
     Ok(())
 }
 
+// Every option may also be supplied through a `SPAM_STATS_*` environment variable. Options that
+// accept multiple values are split on commas, whether they're read from the environment or from
+// the command line, so a single path containing a comma can't be passed.
 #[derive(clap::Parser)]
 struct Args {
     /// The virtual mailbox base path
-    #[clap(value_parser, short, long)]
+    #[clap(value_parser, short, long, env = "SPAM_STATS_PATH")]
     path: String,
 
-    /// Additional Maildir paths to parse through
-    #[clap(value_parser, short, long)]
+    /// Additional Maildir paths to parse through, comma-separated or repeated
+    #[clap(
+        value_parser,
+        short,
+        long,
+        env = "SPAM_STATS_MAILDIRS",
+        value_delimiter = ','
+    )]
     maildirs: Vec<String>,
+
+    /// Recipient addresses that only ever receive spam, comma-separated or repeated. Mail sent to
+    /// them, and their whole mailbox in the virtual mailbox base if they have one, is reported
+    /// separately from the misclassification statistics
+    #[clap(
+        value_parser,
+        short,
+        long,
+        env = "SPAM_STATS_SPAMTRAPS",
+        value_delimiter = ','
+    )]
+    spamtraps: Vec<String>,
+
+    /// Exit with a failure status if any message failed to parse. Sources that can't be loaded
+    /// always cause a failure status
+    #[clap(
+        long,
+        env = "SPAM_STATS_FAIL_ON_PARSE_ERROR",
+        value_parser = clap::builder::FalseyValueParser::new()
+    )]
+    fail_on_parse_error: bool,
+
+    /// Exit with a failure status if no spam was found
+    #[clap(
+        long,
+        env = "SPAM_STATS_FAIL_ON_EMPTY",
+        value_parser = clap::builder::FalseyValueParser::new()
+    )]
+    fail_on_empty: bool,
+
+    /// Exit with a failure status if the report couldn't be sent
+    #[clap(
+        long,
+        env = "SPAM_STATS_FAIL_ON_SEND_ERROR",
+        value_parser = clap::builder::FalseyValueParser::new()
+    )]
+    fail_on_send_error: bool,
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let args = Args::parse();
+fn run(args: Args) -> Result<(), Failure> {
     let domain = get_hostname()?;
-    spam_statistics(&domain, args.path, &args.maildirs)
+    let policy = FailurePolicy {
+        fail_on_parse_error: args.fail_on_parse_error,
+        fail_on_empty: args.fail_on_empty,
+        fail_on_send_error: args.fail_on_send_error,
+    };
+    spam_statistics(&domain, args.path, &args.maildirs, &args.spamtraps, policy)
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            eprintln!("{:#}", failure);
+            failure.exit_code()
+        }
+    }
 }
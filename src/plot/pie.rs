@@ -26,6 +26,18 @@ pub struct Slice {
     pub ratio: f64,
 }
 
+impl Color {
+    pub const ALL: [Color; 7] = [
+        Color::Red,
+        Color::Orange,
+        Color::Yellow,
+        Color::Green,
+        Color::Blue,
+        Color::Indigo,
+        Color::Violet,
+    ];
+}
+
 impl From<Color> for RGBColor {
     fn from(value: Color) -> RGBColor {
         match value {
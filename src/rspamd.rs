@@ -12,7 +12,7 @@ static ACTION_REGEX: LazyLock<Regex> =
 
 #[derive(Clone, Debug, thiserror::Error)]
 pub enum RspamdError {
-    #[error("subprocess")]
+    #[error("rspamc failed: {0}")]
     Subprocess(String),
 }
 
@@ -10,7 +10,7 @@ use chrono::{DateTime, Local, NaiveDate};
 use email::Mailbox;
 use regex::Regex;
 
-use crate::statistics::{SpamEmail, SpamResults};
+use crate::statistics::{Occurrences, Source, SpamEmail, SpamResults};
 
 #[derive(Debug, Copy, Clone, thiserror::Error)]
 pub enum EmailError {
@@ -18,7 +18,32 @@ pub enum EmailError {
     MissingOrMalformedHeader,
 }
 
-fn make_spam_email(message: String, date_received: NaiveDate) -> Result<SpamEmail, anyhow::Error> {
+/// The spam loaded from a single [Source], along with the number of messages that couldn't be
+/// parsed, or the reason the source couldn't be loaded at all.
+pub struct LoadedSpam {
+    pub source: Source,
+    pub spam: SpamResults,
+    pub parse_errors: Occurrences,
+    pub load_error: Option<String>,
+}
+
+impl LoadedSpam {
+    /// Record a source that couldn't be loaded.
+    pub fn failed(source: Source, error: anyhow::Error) -> Self {
+        Self {
+            source,
+            spam: Vec::new(),
+            parse_errors: 0,
+            load_error: Some(format!("{:#}", error)),
+        }
+    }
+}
+
+fn make_spam_email(
+    message: String,
+    date_received: NaiveDate,
+    source: Source,
+) -> Result<SpamEmail, anyhow::Error> {
     static SPAMD_RESULT_REGEX: LazyLock<Regex> =
         LazyLock::new(|| Regex::new(r"[^\[]*\[(-?[.0-9]*)").unwrap());
 
@@ -57,16 +82,28 @@ fn make_spam_email(message: String, date_received: NaiveDate) -> Result<SpamEmai
         .ok_or(EmailError::MissingOrMalformedHeader)?
         .get_value::<String>()?;
 
+    // Prefer the original envelope recipient, which is the only header that still names an
+    // address after alias expansion. Delivered-To names the final mailbox, and the To header may
+    // list several addresses or none of ours at all.
+    let recipient = ["X-Original-To", "Delivered-To", "To"]
+        .into_iter()
+        .filter_map(|name| headers.get(name.to_string()))
+        .filter_map(|header| header.get_value::<String>().ok())
+        .find_map(|value| value.parse::<Mailbox>().ok())
+        .map(|mailbox| mailbox.address);
+
     let spam_result: f64 = spam_result.as_str().parse()?;
     Ok(SpamEmail {
         date_received,
         spam_result,
         is_spam,
         from,
+        recipient,
+        source,
     })
 }
 
-fn load_spam<P>(path: P) -> anyhow::Result<SpamEmail>
+fn load_spam<P>(path: P, source: Source) -> anyhow::Result<SpamEmail>
 where
     P: AsRef<Path>,
 {
@@ -77,15 +114,16 @@ where
 
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
-    make_spam_email(contents, date_received.date_naive())
+    make_spam_email(contents, date_received.date_naive(), source)
 }
 
-fn list_spam_maildir<P>(path: P) -> anyhow::Result<Vec<PathBuf>>
+/// List the messages in a single maildir folder
+fn list_maildir_folder<P>(path: P) -> anyhow::Result<Vec<PathBuf>>
 where
     P: AsRef<Path>,
 {
     let mut spam: Vec<PathBuf> = Vec::new();
-    let spam_folder = path.as_ref().join(".Spam");
+    let spam_folder = path.as_ref();
 
     // See maildir(5)
     let read = spam_folder.join("cur");
@@ -111,6 +149,13 @@ where
     Ok(spam)
 }
 
+fn list_spam_maildir<P>(path: P) -> anyhow::Result<Vec<PathBuf>>
+where
+    P: AsRef<Path>,
+{
+    list_maildir_folder(path.as_ref().join(".Spam"))
+}
+
 /// Return a list of the top spam-sending domains
 fn top_offending_domains<S, I>(iter: I) -> Vec<(String, usize)>
 where
@@ -147,7 +192,10 @@ where
     counts
 }
 
-pub fn domain_report(spam: impl Iterator<Item = SpamEmail>) -> String {
+pub fn domain_report<S>(spam: impl Iterator<Item = S>) -> String
+where
+    S: AsRef<SpamEmail>,
+{
     let domains = top_offending_domains(spam);
     "<h3>Misclassified Domains</h3>".to_string()
         + "<p>Domains that have sent mail misclassified as ham.</p>"
@@ -160,17 +208,185 @@ pub fn domain_report(spam: impl Iterator<Item = SpamEmail>) -> String {
         + "</ul>"
 }
 
-pub fn load_spam_maildir<P>(path: P) -> anyhow::Result<SpamResults>
+/// Return a list of the senders that have most frequently delivered mail to spamtraps
+fn top_spamtrap_senders<S, I>(iter: I) -> Vec<(String, usize)>
+where
+    I: Iterator<Item = S>,
+    S: AsRef<SpamEmail>,
+{
+    let mut counts = HashMap::<String, usize>::new();
+    for message in iter {
+        let message = message.as_ref();
+        let sender = message
+            .from
+            .parse::<Mailbox>()
+            .map(|mailbox| mailbox.address)
+            .unwrap_or_else(|_| message.from.clone());
+        let count = counts.entry(sender.to_lowercase()).or_default();
+        *count += 1;
+    }
+
+    // Break ties by sender, so the same senders make the cut in every report
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    counts.sort_by(|(one_sender, one), (two_sender, two)| {
+        two.cmp(one).then_with(|| one_sender.cmp(two_sender))
+    });
+    counts
+}
+
+pub fn spamtrap_report(traps: &[&SpamEmail], max_senders: usize) -> String {
+    let senders = top_spamtrap_senders(traps.iter());
+    "<h3>Spamtrap Hits</h3>".to_string()
+        + &format!(
+            "<p>{} messages were delivered to spamtrap addresses.</p>",
+            traps.len()
+        )
+        + r#"<ul style="list-style-type:none;">"#
+        + &senders
+            .iter()
+            .take(max_senders)
+            .map(|(sender, count)| format!("<li>{}: {}</li>\n", sender, count))
+            .collect::<Vec<_>>()
+            .join("\n")
+        + "</ul>"
+}
+
+/// Split the spam results into messages that were loaded from a spamtrap mailbox or delivered to
+/// one of the spamtrap addresses, and those that were not. Mail delivered to a spamtrap is known
+/// to be spam, regardless of the classification Rspamd assigned to it.
+pub fn partition_spamtraps<'a, S>(
+    spam: &'a [SpamEmail],
+    spamtraps: &[S],
+) -> (Vec<&'a SpamEmail>, Vec<&'a SpamEmail>)
+where
+    S: AsRef<str>,
+{
+    spam.iter().partition(|email| {
+        matches!(email.source, Source::Spamtrap(_))
+            || email.recipient.as_ref().is_some_and(|recipient| {
+                spamtraps
+                    .iter()
+                    .any(|trap| trap.as_ref().eq_ignore_ascii_case(recipient))
+            })
+    })
+}
+
+pub fn source_report(spam: &[SpamEmail], sources: &[LoadedSpam]) -> String {
+    "<h3>Sources</h3>".to_string()
+        + "<p>Messages loaded from each source, and messages that failed to parse.</p>"
+        + r#"<ul style="list-style-type:none;">"#
+        + &sources
+            .iter()
+            .map(|loaded| {
+                let source = &loaded.source;
+                let received = spam.iter().filter(|email| email.source == *source).count();
+                match &loaded.load_error {
+                    Some(error) => format!(
+                        "<li>{}: {} messages, failed to load: {}</li>\n",
+                        source, received, error
+                    ),
+                    None => format!(
+                        "<li>{}: {} messages, {} parse errors</li>\n",
+                        source, received, loaded.parse_errors
+                    ),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+        + "</ul>"
+}
+
+pub fn load_spam_maildir<P>(path: P) -> anyhow::Result<LoadedSpam>
 where
     P: AsRef<Path>,
 {
-    Ok(list_spam_maildir(path)?
-        .into_iter()
-        .filter_map(|email| load_spam(email).ok())
-        .collect::<SpamResults>())
+    if !path.as_ref().is_dir() {
+        return Err(anyhow::anyhow!(
+            "maildir {} does not exist",
+            path.as_ref().display()
+        ));
+    }
+
+    let source = Source::Maildir(path.as_ref().to_path_buf());
+    let mut spam = Vec::new();
+    let mut parse_errors = 0;
+    for email in list_spam_maildir(path)? {
+        match load_spam(&email, source.clone()) {
+            Ok(spam_email) => spam.push(spam_email),
+            Err(error) => {
+                eprintln!("{}: {:#}", email.display(), error);
+                parse_errors += 1;
+            }
+        }
+    }
+
+    Ok(LoadedSpam {
+        source,
+        spam,
+        parse_errors,
+        load_error: None,
+    })
+}
+
+/// The mailbox of a spamtrap address in the virtual mailbox base, i.e. `<base>/<domain>/<user>`
+pub fn spamtrap_mailbox<P>(virtual_mailbox_base: P, address: &str) -> Option<PathBuf>
+where
+    P: AsRef<Path>,
+{
+    let (user, domain) = address.split_once("@")?;
+    Some(virtual_mailbox_base.as_ref().join(domain).join(user))
+}
+
+/// Load every message delivered to a spamtrap address. Since all mail to a spamtrap is spam, this
+/// includes the inbox, and not just the mail that Rspamd sorted into the Spam folder. Returns
+/// `None` if the spamtrap has no mailbox of its own, e.g. because it's an alias. Hits on those
+/// spamtraps are found by recipient instead.
+pub fn load_spamtrap<P>(
+    virtual_mailbox_base: P,
+    address: &str,
+) -> anyhow::Result<Option<LoadedSpam>>
+where
+    P: AsRef<Path>,
+{
+    let mailbox = spamtrap_mailbox(virtual_mailbox_base, address)
+        .ok_or_else(|| anyhow::anyhow!("{} is not a valid spamtrap address", address))?;
+    if !mailbox.is_dir() {
+        return Ok(None);
+    }
+
+    let source = Source::Spamtrap(mailbox.clone());
+    let mut messages = list_maildir_folder(&mailbox)?;
+    messages.append(&mut list_spam_maildir(&mailbox)?);
+
+    let mut spam = Vec::new();
+    let mut parse_errors = 0;
+    for message in messages {
+        match load_spam(&message, source.clone()) {
+            Ok(mut spam_email) => {
+                spam_email.recipient = Some(address.to_string());
+                spam.push(spam_email);
+            }
+            Err(error) => {
+                eprintln!("{}: {:#}", message.display(), error);
+                parse_errors += 1;
+            }
+        }
+    }
+
+    Ok(Some(LoadedSpam {
+        source,
+        spam,
+        parse_errors,
+        load_error: None,
+    }))
 }
 
-fn list_spam_virtual_mailbox_base<P>(path: P) -> Result<Vec<PathBuf>, anyhow::Error>
+/// List the spam in each mailbox of the virtual mailbox base, skipping the mailboxes in
+/// `excluded`.
+fn list_spam_virtual_mailbox_base<P>(
+    path: P,
+    excluded: &[PathBuf],
+) -> Result<Vec<PathBuf>, anyhow::Error>
 where
     P: AsRef<Path>,
 {
@@ -179,7 +395,11 @@ where
     for domain in domains {
         if let Ok(users) = domain?.path().read_dir() {
             for user in users {
-                spam.append(&mut list_spam_maildir(user?.path())?);
+                let user = user?.path();
+                if excluded.contains(&user) {
+                    continue;
+                }
+                spam.append(&mut list_spam_maildir(user)?);
             }
         }
     }
@@ -187,18 +407,31 @@ where
     Ok(spam)
 }
 
-pub fn load_spam_virtual_mailbox_base<P>(path: P) -> Result<SpamResults, anyhow::Error>
+pub fn load_spam_virtual_mailbox_base<P>(
+    path: P,
+    excluded: &[PathBuf],
+) -> Result<LoadedSpam, anyhow::Error>
 where
     P: AsRef<Path>,
 {
-    let spam = list_spam_virtual_mailbox_base(path)?;
+    let source = Source::VirtualMailboxBase(path.as_ref().to_path_buf());
+    let spam = list_spam_virtual_mailbox_base(path, excluded)?;
     let mut spam_results = Vec::new();
+    let mut parse_errors = 0;
     for path in spam {
-        match load_spam(path) {
+        match load_spam(&path, source.clone()) {
             Ok(spam_email) => spam_results.push(spam_email),
-            Err(error) => eprintln!("{}", error),
+            Err(error) => {
+                eprintln!("{}: {:#}", path.display(), error);
+                parse_errors += 1;
+            }
         }
     }
 
-    Ok(spam_results)
+    Ok(LoadedSpam {
+        source,
+        spam: spam_results,
+        parse_errors,
+        load_error: None,
+    })
 }
@@ -1,5 +1,5 @@
-use core::hash;
-use std::{collections::HashMap, vec};
+use core::{fmt, hash};
+use std::{collections::HashMap, path::PathBuf, vec};
 
 use chrono::{Datelike, Days, Local, NaiveDate};
 
@@ -10,12 +10,32 @@ pub type SpamResult = f64;
 /// The number of occurrences of an event.
 pub type Occurrences = usize;
 
+/// The input that a [SpamEmail] was loaded from.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Source {
+    VirtualMailboxBase(PathBuf),
+    Maildir(PathBuf),
+    Spamtrap(PathBuf),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::VirtualMailboxBase(path) => write!(f, "{} (virtual)", path.display()),
+            Source::Maildir(path) => write!(f, "{}", path.display()),
+            Source::Spamtrap(path) => write!(f, "{} (spamtrap)", path.display()),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct SpamEmail {
     pub date_received: NaiveDate,
     pub spam_result: SpamResult,
     pub is_spam: bool,
     pub from: String,
+    pub recipient: Option<String>,
+    pub source: Source,
 }
 
 impl AsRef<SpamEmail> for SpamEmail {